@@ -1,20 +1,15 @@
 use crate::*;
 use downcast_rs::{impl_downcast, Downcast};
+use std::alloc::Layout;
 use std::any::TypeId;
 use std::cell::UnsafeCell;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt::Debug;
-use std::mem::size_of;
+use std::ptr::NonNull;
 use std::sync::atomic::AtomicIsize;
 use std::sync::Arc;
 
-impl_downcast!(ComponentStorage);
-trait ComponentStorage: Downcast + Debug {
-    fn remove(&mut self, id: ComponentID);
-    fn len(&self) -> usize;
-}
-
 #[derive(Debug)]
 struct UnsafeVec<T>(UnsafeCell<Vec<T>>);
 
@@ -32,35 +27,314 @@ impl<T: Debug> UnsafeVec<T> {
     }
 }
 
-impl<T: Debug + 'static> ComponentStorage for UnsafeVec<T> {
-    fn remove(&mut self, id: ComponentID) {
-        unsafe {
-            self.inner_mut().swap_remove(id as usize);
+/// A single heap allocation backing every component column of a chunk, so a
+/// row's worth of components land in one contiguous region instead of being
+/// scattered across one allocation per component type.
+#[derive(Debug)]
+struct ComponentBlock {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl ComponentBlock {
+    fn alloc(layout: Layout) -> ComponentBlock {
+        let ptr = if layout.size() == 0 {
+            // No allocation, but keep the pointer aligned to `layout.align()`.
+            NonNull::new(layout.align() as *mut u8).unwrap()
+        } else {
+            let raw = unsafe { std::alloc::alloc(layout) };
+            NonNull::new(raw).unwrap_or_else(|| std::alloc::handle_alloc_error(layout))
+        };
+        ComponentBlock { ptr, layout }
+    }
+
+    unsafe fn offset(&self, byte_offset: usize) -> *mut u8 {
+        self.ptr.as_ptr().add(byte_offset)
+    }
+}
+
+impl Drop for ComponentBlock {
+    fn drop(&mut self) {
+        if self.layout.size() != 0 {
+            unsafe { std::alloc::dealloc(self.ptr.as_ptr(), self.layout) };
         }
     }
+}
+
+/// Rounds `offset` up to the nearest multiple of `align` (a power of two).
+fn align_up(offset: usize, align: usize) -> usize {
+    (offset + align - 1) & !(align - 1)
+}
 
-    fn len(&self) -> usize {
-        unsafe { self.inner_mut().len() }
+unsafe fn drop_one<T>(ptr: *mut u8) {
+    std::ptr::drop_in_place(ptr as *mut T);
+}
+
+unsafe fn swap_remove<T>(base: *mut u8, idx: usize, len: usize) {
+    let base = base as *mut T;
+    if idx != len - 1 {
+        std::ptr::copy_nonoverlapping(base.add(len - 1), base.add(idx), 1);
+    }
+}
+
+/// A single component type's array inside a chunk's `ComponentBlock`: a
+/// type-erased, properly aligned run of `capacity` elements, with drop and
+/// swap-removal glue captured at registration time so `Chunk` never needs to
+/// downcast back to `T` to manage the column's memory.
+struct ComponentColumn {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    capacity: usize,
+    drop_one: unsafe fn(*mut u8),
+    swap_remove: unsafe fn(*mut u8, usize, usize),
+}
+
+impl Debug for ComponentColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ComponentColumn")
+            .field("ptr", &self.ptr)
+            .field("layout", &self.layout)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl ComponentColumn {
+    unsafe fn element(&self, idx: usize) -> *mut u8 {
+        self.ptr.as_ptr().add(idx * self.layout.size())
+    }
+
+    /// Drops the element at `idx` (which must hold a live value) and moves the
+    /// element at `len - 1` into its place, as in `Vec::swap_remove`.
+    unsafe fn remove(&self, idx: usize, len: usize) {
+        (self.drop_one)(self.element(idx));
+        (self.swap_remove)(self.ptr.as_ptr(), idx, len);
+    }
+
+    /// Drops all `len` live elements, for releasing a whole chunk.
+    unsafe fn drop_all(&self, len: usize) {
+        for i in 0..len {
+            (self.drop_one)(self.element(i));
+        }
+    }
+
+    /// Moves the live element at `idx` into `dest`'s `dest_idx` slot (`dest`
+    /// must have room) and closes the gap it leaves behind with a swap from
+    /// the last element, without running any drop glue.
+    unsafe fn move_out(&self, idx: usize, len: usize, dest: &ComponentColumn, dest_idx: usize) {
+        std::ptr::copy_nonoverlapping(
+            self.element(idx),
+            dest.element(dest_idx),
+            self.layout.size(),
+        );
+        (self.swap_remove)(self.ptr.as_ptr(), idx, len);
+    }
+}
+
+/// Error returned by [`Chunk::zip_mut`]: either one of the requested
+/// component types isn't present in the chunk at all, or acquiring one of
+/// the borrows conflicted with another outstanding one.
+#[derive(Debug)]
+pub enum ZipError {
+    MissingComponent,
+    Borrow(BorrowError),
+}
+
+impl From<BorrowError> for ZipError {
+    fn from(err: BorrowError) -> ZipError {
+        ZipError::Borrow(err)
+    }
+}
+
+/// Marks a `Chunk::zip_mut` query parameter as wanting a shared borrow of `T`.
+pub struct Read<T>(std::marker::PhantomData<T>);
+
+/// Marks a `Chunk::zip_mut` query parameter as wanting a unique borrow of `T`.
+pub struct Write<T>(std::marker::PhantomData<T>);
+
+/// One element of a `Chunk::zip_mut` query: acquires the right kind of
+/// `Borrow` for its component type and hands back a per-row iterator over it.
+trait ColumnFetch<'a> {
+    type Item;
+    type Iter: Iterator<Item = Self::Item>;
+
+    fn type_id() -> TypeId;
+
+    unsafe fn acquire(chunk: &'a Chunk) -> Result<(Self::Iter, Borrow<'a>), ZipError>;
+}
+
+impl<'a, T: EntityData> ColumnFetch<'a> for Read<T> {
+    type Item = &'a T;
+    type Iter = std::slice::Iter<'a, T>;
+
+    fn type_id() -> TypeId {
+        TypeId::of::<T>()
+    }
+
+    unsafe fn acquire(chunk: &'a Chunk) -> Result<(Self::Iter, Borrow<'a>), ZipError> {
+        let data = chunk
+            .entity_data_unchecked::<T>()
+            .ok_or(ZipError::MissingComponent)?;
+        let borrow = Borrow::aquire_read(chunk.borrow_state::<T>())?;
+        Ok((data.iter(), borrow))
+    }
+}
+
+impl<'a, T: EntityData> ColumnFetch<'a> for Write<T> {
+    type Item = &'a mut T;
+    type Iter = std::slice::IterMut<'a, T>;
+
+    fn type_id() -> TypeId {
+        TypeId::of::<T>()
+    }
+
+    unsafe fn acquire(chunk: &'a Chunk) -> Result<(Self::Iter, Borrow<'a>), ZipError> {
+        let data = chunk
+            .entity_data_unchecked::<T>()
+            .ok_or(ZipError::MissingComponent)?;
+        let borrow = Borrow::aquire_write(chunk.borrow_state::<T>())?;
+        Ok((data.iter_mut(), borrow))
+    }
+}
+
+/// A `Chunk::zip_mut` iterator bundled with the `Borrow` guards it depends
+/// on, so the borrows stay held for exactly as long as the iterator lives.
+struct ZipIter<'a, I> {
+    iter: I,
+    _borrows: Vec<Borrow<'a>>,
+}
+
+impl<'a, I: Iterator> Iterator for ZipIter<'a, I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        self.iter.next()
+    }
+}
+
+/// Like [`std::iter::Zip`] but over three iterators at once, so the 3-tuple
+/// `ComponentTuple` impl can yield a flat `(a, b, c)` item instead of the
+/// nested `((a, b), c)` shape two chained `Zip`s would produce.
+struct Zip3<A, B, C> {
+    a: A,
+    b: B,
+    c: C,
+}
+
+impl<A: Iterator, B: Iterator, C: Iterator> Iterator for Zip3<A, B, C> {
+    type Item = (A::Item, B::Item, C::Item);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let a = self.a.next()?;
+        let b = self.b.next()?;
+        let c = self.c.next()?;
+        Some((a, b, c))
+    }
+}
+
+/// A tuple of `Read<T>`/`Write<T>` markers describing the columns a
+/// `Chunk::zip_mut` query wants to iterate together. Implemented for 2- and
+/// 3-tuples; add further arities here following the same pattern if a query
+/// needs more columns than that.
+pub trait ComponentTuple<'a> {
+    type Item;
+    type Iter: Iterator<Item = Self::Item>;
+
+    unsafe fn zip_mut(chunk: &'a Chunk) -> Result<Self::Iter, ZipError>;
+}
+
+impl<'a, A, B> ComponentTuple<'a> for (A, B)
+where
+    A: ColumnFetch<'a> + 'a,
+    B: ColumnFetch<'a> + 'a,
+{
+    type Item = (A::Item, B::Item);
+    type Iter = ZipIter<'a, std::iter::Zip<A::Iter, B::Iter>>;
+
+    unsafe fn zip_mut(chunk: &'a Chunk) -> Result<Self::Iter, ZipError> {
+        if A::type_id() == B::type_id() {
+            panic!("Chunk::zip_mut requested the same component type twice");
+        }
+
+        let (iter_a, borrow_a) = A::acquire(chunk)?;
+        let (iter_b, borrow_b) = B::acquire(chunk)?;
+        Ok(ZipIter {
+            iter: iter_a.zip(iter_b),
+            _borrows: vec![borrow_a, borrow_b],
+        })
+    }
+}
+
+impl<'a, A, B, C> ComponentTuple<'a> for (A, B, C)
+where
+    A: ColumnFetch<'a> + 'a,
+    B: ColumnFetch<'a> + 'a,
+    C: ColumnFetch<'a> + 'a,
+{
+    type Item = (A::Item, B::Item, C::Item);
+    type Iter = ZipIter<'a, Zip3<A::Iter, B::Iter, C::Iter>>;
+
+    unsafe fn zip_mut(chunk: &'a Chunk) -> Result<Self::Iter, ZipError> {
+        if A::type_id() == B::type_id()
+            || A::type_id() == C::type_id()
+            || B::type_id() == C::type_id()
+        {
+            panic!("Chunk::zip_mut requested the same component type twice");
+        }
+
+        let (iter_a, borrow_a) = A::acquire(chunk)?;
+        let (iter_b, borrow_b) = B::acquire(chunk)?;
+        let (iter_c, borrow_c) = C::acquire(chunk)?;
+        Ok(ZipIter {
+            iter: Zip3 {
+                a: iter_a,
+                b: iter_b,
+                c: iter_c,
+            },
+            _borrows: vec![borrow_a, borrow_b, borrow_c],
+        })
     }
 }
 
 impl_downcast!(SharedComponentStorage);
-trait SharedComponentStorage: Downcast + Debug {}
+trait SharedComponentStorage: Downcast + Debug {
+    /// Whether `self` and `other` hold equal shared component *values*.
+    /// `false` if `other` isn't storing the same component type.
+    fn values_eq(&self, other: &dyn SharedComponentStorage) -> bool;
+}
 
 #[derive(Debug)]
 struct SharedComponentStore<T>(UnsafeCell<T>);
 
-impl<T: SharedData> SharedComponentStorage for SharedComponentStore<T> {}
+impl<T: SharedData + PartialEq> SharedComponentStorage for SharedComponentStore<T> {
+    fn values_eq(&self, other: &dyn SharedComponentStorage) -> bool {
+        match other.downcast_ref::<SharedComponentStore<T>>() {
+            Some(other) => unsafe { *self.0.get() == *other.0.get() },
+            None => false,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Chunk {
     capacity: usize,
     entities: UnsafeVec<Entity>,
-    components: HashMap<TypeId, Box<dyn ComponentStorage>>,
+    #[allow(dead_code)]
+    block: ComponentBlock,
+    columns: HashMap<TypeId, ComponentColumn>,
     shared: HashMap<TypeId, Arc<dyn SharedComponentStorage>>,
     borrows: HashMap<TypeId, AtomicIsize>,
 }
 
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        let len = self.len();
+        for column in self.columns.values() {
+            unsafe { column.drop_all(len) };
+        }
+    }
+}
+
 impl Chunk {
     pub fn len(&self) -> usize {
         unsafe { self.entities.inner().len() }
@@ -78,11 +352,10 @@ impl Chunk {
         self.entities.inner_mut()
     }
 
-    pub unsafe fn entity_data_unchecked<T: EntityData>(&self) -> Option<&mut Vec<T>> {
-        self.components
+    pub unsafe fn entity_data_unchecked<T: EntityData>(&self) -> Option<&mut [T]> {
+        self.columns
             .get(&TypeId::of::<T>())
-            .and_then(|c| c.downcast_ref::<UnsafeVec<T>>())
-            .map(|c| c.inner_mut())
+            .map(|column| std::slice::from_raw_parts_mut(column.ptr.as_ptr() as *mut T, self.len()))
     }
 
     pub fn entity_data<'a, T: EntityData>(&'a self) -> Option<BorrowedSlice<'a, T>> {
@@ -105,6 +378,45 @@ impl Chunk {
         }
     }
 
+    /// Like [`entity_data`](Chunk::entity_data), but reports a conflicting
+    /// borrow as a `BorrowError` instead of panicking.
+    pub fn try_entity_data<'a, T: EntityData>(
+        &'a self,
+    ) -> Result<Option<BorrowedSlice<'a, T>>, BorrowError> {
+        match unsafe { self.entity_data_unchecked() } {
+            Some(data) => {
+                let borrow = Borrow::aquire_read(self.borrow_state::<T>())?;
+                Ok(Some(BorrowedSlice::new(data, borrow)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Like [`entity_data_mut`](Chunk::entity_data_mut), but reports a
+    /// conflicting borrow as a `BorrowError` instead of panicking.
+    pub fn try_entity_data_mut<'a, T: EntityData>(
+        &'a self,
+    ) -> Result<Option<BorrowedMutSlice<'a, T>>, BorrowError> {
+        match unsafe { self.entity_data_unchecked() } {
+            Some(data) => {
+                let borrow = Borrow::aquire_write(self.borrow_state::<T>())?;
+                Ok(Some(BorrowedMutSlice::new(data, borrow)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Iterates a chunk's rows, yielding a `Read<T>`/`Write<T>` tuple of
+    /// per-entity references for each requested component type. Acquires the
+    /// right `Borrow` for every element of `Q` in one call and rejects
+    /// queries that alias the same component type (e.g. `Write<T>` with
+    /// `Read<T>`), so simultaneous mutable and shared access across several
+    /// components in a chunk is safe. Returns `Err(ZipError::MissingComponent)`
+    /// rather than panicking if the chunk doesn't have one of `Q`'s types.
+    pub fn zip_mut<'a, Q: ComponentTuple<'a>>(&'a self) -> Result<Q::Iter, ZipError> {
+        unsafe { Q::zip_mut(self) }
+    }
+
     pub unsafe fn shared_component<T: SharedData>(&self) -> Option<&T> {
         self.shared
             .get(&TypeId::of::<T>())
@@ -114,9 +426,11 @@ impl Chunk {
 
     pub unsafe fn remove(&mut self, id: ComponentID) -> Option<Entity> {
         let index = id as usize;
+        let len = self.entities.inner().len();
         self.entities.inner_mut().swap_remove(index);
-        for storage in self.components.values_mut() {
-            storage.remove(id);
+
+        for column in self.columns.values() {
+            column.remove(index, len);
         }
 
         if self.entities.len() > index {
@@ -127,40 +441,107 @@ impl Chunk {
     }
 
     pub fn validate(&self) {
-        let valid = self
-            .components
-            .values()
-            .fold(true, |total, s| total && s.len() == self.entities.len());
-        if !valid {
+        if self.entities.len() > self.capacity {
             panic!("imbalanced chunk components");
         }
     }
 
-    fn borrow<'a, T: EntityData>(&'a self) -> Borrow<'a> {
+    /// Whether every column's borrow flag is free, i.e. it is safe to move
+    /// rows into or out of this chunk.
+    fn is_unborrowed(&self) -> bool {
+        self.borrows
+            .values()
+            .all(|state| state.load(std::sync::atomic::Ordering::Acquire) == 0)
+    }
+
+    /// Whether `self` and `other` were built with the same shared component
+    /// values, mirroring the match check `Archetype::get_or_create_chunk`
+    /// uses when looking for a chunk to place new entities in. Compares the
+    /// stored values themselves rather than `Arc` pointer identity, since
+    /// every chunk gets its own freshly-allocated `Arc` from
+    /// `ChunkBuilder::register_shared` even when two chunks were configured
+    /// with logically identical shared data.
+    fn shared_matches(&self, other: &Chunk) -> bool {
+        self.shared.len() == other.shared.len()
+            && self.shared.iter().all(|(id, data)| {
+                other
+                    .shared
+                    .get(id)
+                    .map_or(false, |other_data| data.values_eq(other_data.as_ref()))
+            })
+    }
+
+    /// Moves the row at `index` out of `self` and into `dest` (which must
+    /// have spare capacity), returning the entity that moved. The vacated
+    /// slot in `self` is closed with a swap from the last row, as in
+    /// [`remove`](Chunk::remove), but without dropping anything since the
+    /// data is still alive in `dest`.
+    fn move_row_into(&mut self, index: usize, dest: &mut Chunk) -> Entity {
+        let len = self.entities.inner().len();
+        let entity = *self.entities.inner().get(index).unwrap();
+        let dest_index = dest.len();
+
+        for (id, column) in &self.columns {
+            let dest_column = dest
+                .columns
+                .get(id)
+                .expect("Archetype::compact: mismatched chunk layout");
+            unsafe { column.move_out(index, len, dest_column, dest_index) };
+        }
+
+        self.entities.inner_mut().swap_remove(index);
+        dest.entities.inner_mut().push(entity);
+        entity
+    }
+
+    fn borrow_state<T: EntityData>(&self) -> &AtomicIsize {
         let id = TypeId::of::<T>();
-        let state = self
-            .borrows
+        self.borrows
             .get(&id)
-            .expect("entity data type not found in chunk");
-        Borrow::aquire_read(state).unwrap()
+            .expect("entity data type not found in chunk")
+    }
+
+    /// Rewrites an outstanding unique borrow of `T` into a single shared
+    /// borrow in one atomic step, so the flag never passes through the fully
+    /// released (zero) state. Restricted to the crate rather than exposed
+    /// publicly: it's only meant to be called from the guard-consuming
+    /// `Borrow::downgrade`/`BorrowedMutSlice::downgrade` conversions (in the
+    /// module where `Borrow`/`BorrowedMutSlice` are declared), which
+    /// actually hold the unique borrow being downgraded and forget it
+    /// afterwards instead of letting it drop. Calling this without holding
+    /// that borrow corrupts the chunk's accounting for every later caller.
+    ///
+    /// # Safety
+    /// The caller must currently hold, and must treat as consumed after this
+    /// call, the chunk's one outstanding unique borrow of `T`.
+    pub(crate) unsafe fn downgrade_borrow<T: EntityData>(&self) {
+        let state = self.borrow_state::<T>();
+        debug_assert_eq!(
+            state.load(std::sync::atomic::Ordering::Acquire),
+            -1,
+            "downgrade_borrow called without the chunk's outstanding unique borrow of T"
+        );
+        state.store(1, std::sync::atomic::Ordering::Release);
+    }
+
+    fn borrow<'a, T: EntityData>(&'a self) -> Borrow<'a> {
+        Borrow::aquire_read(self.borrow_state::<T>()).unwrap()
     }
 
     fn borrow_mut<'a, T: EntityData>(&'a self) -> Borrow<'a> {
-        let id = TypeId::of::<T>();
-        let state = self
-            .borrows
-            .get(&id)
-            .expect("entity data type not found in chunk");
-        Borrow::aquire_write(state).unwrap()
+        Borrow::aquire_write(self.borrow_state::<T>()).unwrap()
     }
 }
 
+struct ComponentSpec {
+    id: TypeId,
+    layout: Layout,
+    drop_one: unsafe fn(*mut u8),
+    swap_remove: unsafe fn(*mut u8, usize, usize),
+}
+
 pub struct ChunkBuilder {
-    components: Vec<(
-        TypeId,
-        usize,
-        Box<dyn FnMut(usize) -> Box<dyn ComponentStorage>>,
-    )>,
+    components: Vec<ComponentSpec>,
     shared: HashMap<TypeId, Arc<dyn SharedComponentStorage>>,
 }
 
@@ -175,14 +556,15 @@ impl ChunkBuilder {
     }
 
     pub fn register_component<T: EntityData>(&mut self) {
-        let constructor = |capacity| {
-            Box::new(UnsafeVec::<T>::with_capacity(capacity)) as Box<dyn ComponentStorage>
-        };
-        self.components
-            .push((TypeId::of::<T>(), size_of::<T>(), Box::new(constructor)));
+        self.components.push(ComponentSpec {
+            id: TypeId::of::<T>(),
+            layout: Layout::new::<T>(),
+            drop_one: drop_one::<T>,
+            swap_remove: swap_remove::<T>,
+        });
     }
 
-    pub fn register_shared<T: SharedData>(&mut self, data: T) {
+    pub fn register_shared<T: SharedData + PartialEq>(&mut self, data: T) {
         self.shared.insert(
             TypeId::of::<T>(),
             Arc::new(SharedComponentStore(UnsafeCell::new(data)))
@@ -191,26 +573,86 @@ impl ChunkBuilder {
     }
 
     pub fn build(self) -> Chunk {
-        let size_bytes = *self
+        if self.components.is_empty() {
+            // No entity component data at all (e.g. a shared-component-only
+            // archetype): there's nothing to size a block against, so don't
+            // allocate one, and size the chunk purely by entity count.
+            let capacity = ChunkBuilder::MAX_SIZE;
+            return Chunk {
+                capacity,
+                borrows: HashMap::new(),
+                entities: UnsafeVec::with_capacity(capacity),
+                block: ComponentBlock::alloc(Layout::from_size_align(0, 1).unwrap()),
+                columns: HashMap::new(),
+                shared: self.shared,
+            };
+        }
+
+        // A non-empty component set can still have zero total row size, e.g.
+        // an archetype made entirely of zero-sized marker components. There's
+        // no row width to divide capacity by, so size the chunk purely by
+        // entity count instead of quietly capping it at one entity per chunk.
+        let row_size: usize = self.components.iter().map(|c| c.layout.size()).sum();
+        let capacity = if row_size == 0 {
+            ChunkBuilder::MAX_SIZE
+        } else {
+            std::cmp::max(1, ChunkBuilder::MAX_SIZE / row_size)
+        };
+
+        let max_align = self
             .components
             .iter()
-            .map(|(_, size, _)| size)
+            .map(|c| c.layout.align())
             .max()
-            .unwrap_or(&ChunkBuilder::MAX_SIZE);
-        let capacity = std::cmp::max(1, ChunkBuilder::MAX_SIZE / size_bytes);
+            .unwrap_or(1);
+
+        // Each column starts at an offset aligned to its own type's
+        // alignment. `Layout::size()` is always a multiple of its own
+        // `align()`, so aligning just the start keeps every element in the
+        // column aligned too, not just the first one.
+        let mut offset = 0;
+        let column_offsets: Vec<usize> = self
+            .components
+            .iter()
+            .map(|spec| {
+                offset = align_up(offset, spec.layout.align());
+                let column_offset = offset;
+                offset += spec.layout.size() * capacity;
+                column_offset
+            })
+            .collect();
+
+        let block_layout =
+            Layout::from_size_align(offset, max_align).expect("invalid chunk block layout");
+        let block = ComponentBlock::alloc(block_layout);
+
+        let columns = self
+            .components
+            .iter()
+            .zip(column_offsets)
+            .map(|(spec, column_offset)| {
+                let ptr = NonNull::new(unsafe { block.offset(column_offset) }).unwrap();
+                let column = ComponentColumn {
+                    ptr,
+                    layout: spec.layout,
+                    capacity,
+                    drop_one: spec.drop_one,
+                    swap_remove: spec.swap_remove,
+                };
+                (spec.id, column)
+            })
+            .collect();
+
         Chunk {
-            capacity: capacity,
+            capacity,
             borrows: self
                 .components
                 .iter()
-                .map(|(id, _, _)| (*id, AtomicIsize::new(0)))
+                .map(|spec| (spec.id, AtomicIsize::new(0)))
                 .collect(),
             entities: UnsafeVec::with_capacity(capacity),
-            components: self
-                .components
-                .into_iter()
-                .map(|(id, _, mut con)| (id, con(capacity)))
-                .collect(),
+            block,
+            columns,
             shared: self.shared,
         }
     }
@@ -287,4 +729,237 @@ impl Archetype {
             }
         }
     }
+
+    /// Merges entities out of sparsely-populated chunks into fuller
+    /// shared-component-compatible chunks, dropping chunks that become
+    /// empty, and returns the new `(ChunkID, ComponentID)` of every entity
+    /// whose location changed so callers can fix up their entity-location
+    /// tables. That includes entities that stayed in their chunk when the
+    /// chunk's ID shifted because an earlier chunk was dropped.
+    ///
+    /// Chunks with any outstanding borrow are left untouched.
+    pub fn compact(&mut self) -> Vec<(Entity, ChunkID, ComponentID)> {
+        // Chunks are only dropped once, after the loop below, so chunk
+        // indices recorded in `moved` stay valid until the final remap.
+        let mut moved: Vec<(Entity, usize, ComponentID)> = Vec::new();
+        // Sources found to have no compatible chunk to merge into.
+        let mut no_partner: HashSet<usize> = HashSet::new();
+
+        loop {
+            let source = self
+                .chunks
+                .iter()
+                .enumerate()
+                .filter(|(i, c)| {
+                    !no_partner.contains(i) && c.len() > 0 && !c.is_full() && c.is_unborrowed()
+                })
+                .min_by_key(|(_, c)| c.len())
+                .map(|(i, _)| i);
+            let source = match source {
+                Some(i) => i,
+                None => break,
+            };
+
+            // Prefer the fullest eligible chunk, not just the first one
+            // found, so entities consolidate into as few chunks as possible
+            // instead of merely shuffling between equally-sparse ones.
+            let dest = self
+                .chunks
+                .iter()
+                .enumerate()
+                .filter(|(i, c)| {
+                    *i != source
+                        && c.len() > 0
+                        && !c.is_full()
+                        && c.is_unborrowed()
+                        && self.chunks[source].shared_matches(c)
+                })
+                .max_by_key(|(_, c)| c.len())
+                .map(|(i, _)| i);
+            let dest = match dest {
+                Some(i) => i,
+                None => {
+                    no_partner.insert(source);
+                    continue;
+                }
+            };
+
+            let (source_chunk, dest_chunk) = if source < dest {
+                let (left, right) = self.chunks.split_at_mut(dest);
+                (&mut left[source], &mut right[0])
+            } else {
+                let (left, right) = self.chunks.split_at_mut(source);
+                (&mut right[0], &mut left[dest])
+            };
+
+            while source_chunk.len() > 0 && !dest_chunk.is_full() {
+                let index = source_chunk.len() - 1;
+                let dest_index = dest_chunk.len();
+                let entity = source_chunk.move_row_into(index, dest_chunk);
+                moved.push((entity, dest, dest_index as ComponentID));
+            }
+        }
+
+        let mut remap = vec![0 as ChunkID; self.chunks.len()];
+        let mut next_id: ChunkID = 0;
+        let mut kept = Vec::with_capacity(self.chunks.len());
+        // Every row of a chunk whose ID shifts has a new location, whether or
+        // not it moved between chunks.
+        let mut relocated: Vec<(Entity, ChunkID, ComponentID)> = Vec::new();
+        for (i, chunk) in self.chunks.drain(..).enumerate() {
+            if chunk.len() == 0 {
+                continue;
+            }
+            remap[i] = next_id;
+            if next_id as usize != i {
+                for (index, entity) in unsafe { chunk.entities() }.iter().enumerate() {
+                    relocated.push((*entity, next_id, index as ComponentID));
+                }
+            }
+            next_id += 1;
+            kept.push(chunk);
+        }
+        self.chunks = kept;
+
+        for chunk in &self.chunks {
+            chunk.validate();
+        }
+
+        // Rows moved into a renumbered chunk are already listed above.
+        relocated.extend(
+            moved
+                .into_iter()
+                .filter(|(_, chunk, _)| remap[*chunk] as usize == *chunk)
+                .map(|(entity, chunk, component)| (entity, remap[chunk], component)),
+        );
+        relocated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Position(f32);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Velocity(f32);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Team(u32);
+
+    fn test_logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    #[test]
+    fn compact_merges_chunks_with_equal_shared_component_values() {
+        let mut archetype = Archetype::new(test_logger(), HashSet::new(), HashSet::new());
+
+        let mut sparse = ChunkBuilder::new();
+        sparse.register_component::<Position>();
+        sparse.register_shared(Team(1));
+        let mut sparse_chunk = sparse.build();
+        unsafe { sparse_chunk.entities_unchecked().push(Entity::new(0, 0)) };
+
+        let mut fuller = ChunkBuilder::new();
+        fuller.register_component::<Position>();
+        fuller.register_shared(Team(1));
+        let mut fuller_chunk = fuller.build();
+        for i in 1u32..3 {
+            unsafe { fuller_chunk.entities_unchecked().push(Entity::new(i, 0)) };
+        }
+
+        archetype.chunks.push(sparse_chunk);
+        archetype.chunks.push(fuller_chunk);
+
+        archetype.compact();
+
+        // The two chunks share the same `Team` value, so they must merge
+        // into one instead of `shared_matches` rejecting them over Arc
+        // pointer identity.
+        assert_eq!(archetype.chunks.len(), 1);
+        assert_eq!(archetype.chunks[0].len(), 3);
+    }
+
+    #[test]
+    fn zip_mut_joins_a_write_and_a_read_column() {
+        let mut builder = ChunkBuilder::new();
+        builder.register_component::<Position>();
+        builder.register_component::<Velocity>();
+        let mut chunk = builder.build();
+
+        unsafe {
+            for i in 0..3u32 {
+                chunk.entities_unchecked().push(Entity::new(i, 0));
+            }
+            let positions = chunk.entity_data_unchecked::<Position>().unwrap();
+            let velocities = chunk.entity_data_unchecked::<Velocity>().unwrap();
+            for i in 0..3 {
+                positions[i] = Position(0.0);
+                velocities[i] = Velocity(i as f32 + 1.0);
+            }
+        }
+
+        {
+            let rows = chunk
+                .zip_mut::<(Write<Position>, Read<Velocity>)>()
+                .expect("both columns are present and unborrowed");
+            for (position, velocity) in rows {
+                position.0 += velocity.0;
+            }
+        }
+
+        let positions = unsafe { chunk.entity_data_unchecked::<Position>().unwrap() };
+        assert_eq!(positions, &[Position(1.0), Position(2.0), Position(3.0)]);
+    }
+
+    #[test]
+    fn zip_mut_rejects_querying_the_same_component_twice() {
+        let mut builder = ChunkBuilder::new();
+        builder.register_component::<Position>();
+        let chunk = builder.build();
+
+        // Write<Position> aliased with Read<Position> would hand out a
+        // mutable and a shared reference to the same row at once, so
+        // `zip_mut` must refuse the query outright rather than let the
+        // aliasing through.
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            chunk.zip_mut::<(Write<Position>, Read<Position>)>()
+        }));
+        assert!(result.is_err());
+    }
+
+    #[derive(Debug)]
+    struct DropCounter(Arc<std::sync::atomic::AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn dropping_a_chunk_runs_drop_glue_on_every_live_non_copy_component() {
+        let count = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let mut builder = ChunkBuilder::new();
+        builder.register_component::<DropCounter>();
+        let mut chunk = builder.build();
+
+        unsafe {
+            for i in 0..3u32 {
+                chunk.entities_unchecked().push(Entity::new(i, 0));
+            }
+            let counters = chunk.entity_data_unchecked::<DropCounter>().unwrap();
+            for slot in counters.iter_mut() {
+                std::ptr::write(slot as *mut DropCounter, DropCounter(count.clone()));
+            }
+        }
+
+        drop(chunk);
+
+        assert_eq!(count.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
 }